@@ -1,12 +1,20 @@
 use anyhow::{anyhow, bail, Result};
+use bzip2::read::BzDecoder;
 use clap::{builder::PossibleValue, Parser, ValueEnum};
+use flate2::read::GzDecoder;
 use kseq::{parse_reader, record::Fastx};
-use regex::RegexBuilder;
+use regex::{Regex, RegexBuilder};
 use std::{
     collections::{HashMap, HashSet},
     fs::File,
     io::{self, BufRead, BufReader, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
 };
+use xz2::read::XzDecoder;
 
 #[derive(Parser, Debug)]
 #[command(arg_required_else_help = true, version)]
@@ -44,6 +52,10 @@ pub enum Command {
 #[derive(Debug, Clone, Parser)]
 #[command(author, version, about)]
 pub struct CountArgs {
+    /// Number of threads to parse files in parallel
+    #[arg(short('j'), long, value_name = "NUM")]
+    threads: Option<usize>,
+
     /// Input file(s)
     #[arg(value_name = "FILE", default_value = "-")]
     files: Vec<String>,
@@ -80,6 +92,15 @@ pub struct StatsArgs {
 #[derive(Debug, Clone, Parser)]
 #[command(author, version, about)]
 pub struct FilterArgs {
+    /// Output format
+    #[arg(
+        short('t'),
+        long,
+        value_name = "OUTFMT",
+        value_parser(clap::value_parser!(OutputFormat))
+    )]
+    outfmt: Option<OutputFormat>,
+
     /// Minimum sequence length
     #[arg(short, long("min-len"), value_name = "LEN", default_value = "0")]
     min_length: usize,
@@ -112,14 +133,15 @@ pub struct FilterArgs {
 #[derive(Debug, Clone, Parser)]
 #[command(author, version, about)]
 pub struct GrepArgs {
-    ///// Output format
-    //#[arg(
-    //    short('f'),
-    //    long,
-    //    value_name = "OUTFMT",
-    //    value_parser(clap::value_parser!(OutputFormat))
-    //)]
-    //outfmt: Option<OutputFormat>,
+    /// Output format
+    #[arg(
+        short('f'),
+        long,
+        value_name = "OUTFMT",
+        value_parser(clap::value_parser!(OutputFormat))
+    )]
+    outfmt: Option<OutputFormat>,
+
     /// Output file
     #[arg(short, long, value_name = "OUTPUT")]
     output: Option<String>,
@@ -142,6 +164,18 @@ pub struct GrepArgs {
     #[arg(short('i'), long, value_name = "INSENSITIVE")]
     insensitive: bool,
 
+    /// Show only the matching part, as "id<TAB>start<TAB>end<TAB>match"
+    #[arg(short('m'), long("only-matching"))]
+    only_matching: bool,
+
+    /// Number of threads to search in parallel
+    #[arg(short('j'), long, value_name = "NUM")]
+    threads: Option<usize>,
+
+    /// Print a count of matching records per file instead of the records
+    #[arg(short('c'), long, conflicts_with("only_matching"))]
+    count: bool,
+
     /// Pattern
     #[arg(value_name = "PATTERN")]
     pattern: String,
@@ -176,24 +210,24 @@ impl ValueEnum for GrepRecordPart {
     }
 }
 
-//#[derive(Debug, Eq, PartialEq, Clone)]
-//enum OutputFormat {
-//    Fasta,
-//    Fastq,
-//}
+#[derive(Debug, Eq, PartialEq, Clone)]
+enum OutputFormat {
+    Fasta,
+    Fastq,
+}
 
-//impl ValueEnum for OutputFormat {
-//    fn value_variants<'a>() -> &'a [Self] {
-//        &[OutputFormat::Fasta, OutputFormat::Fastq]
-//    }
+impl ValueEnum for OutputFormat {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[OutputFormat::Fasta, OutputFormat::Fastq]
+    }
 
-//    fn to_possible_value<'a>(&self) -> Option<PossibleValue> {
-//        Some(match self {
-//            OutputFormat::Fasta => PossibleValue::new("fasta"),
-//            OutputFormat::Fastq => PossibleValue::new("fastq"),
-//        })
-//    }
-//}
+    fn to_possible_value<'a>(&self) -> Option<PossibleValue> {
+        Some(match self {
+            OutputFormat::Fasta => PossibleValue::new("fasta"),
+            OutputFormat::Fastq => PossibleValue::new("fastq"),
+        })
+    }
+}
 
 // --------------------------------------------------
 //fn main() {
@@ -230,13 +264,39 @@ pub fn run(args: Cli) -> Result<()> {
     }
 }
 
+// --------------------------------------------------
+// Magic-byte prefixes that identify a compressed stream.
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+
 // --------------------------------------------------
 fn open(filename: &str) -> Result<Box<dyn BufRead>> {
-    match filename {
-        "-" => Ok(Box::new(BufReader::new(io::stdin()))),
-        _ => Ok(Box::new(BufReader::new(
+    let raw: Box<dyn BufRead> = match filename {
+        "-" => Box::new(BufReader::new(io::stdin())),
+        _ => Box::new(BufReader::new(
             File::open(filename).map_err(|e| anyhow!("{filename}: {e}"))?,
-        ))),
+        )),
+    };
+
+    decompress(raw)
+}
+
+// --------------------------------------------------
+// Sniffs the magic bytes at the front of `reader` (without
+// consuming them) and transparently wraps it in the matching
+// streaming decompressor.
+fn decompress(mut reader: Box<dyn BufRead>) -> Result<Box<dyn BufRead>> {
+    let magic = reader.fill_buf()?;
+
+    if magic.starts_with(GZIP_MAGIC) {
+        Ok(Box::new(BufReader::new(GzDecoder::new(reader))))
+    } else if magic.starts_with(BZIP2_MAGIC) {
+        Ok(Box::new(BufReader::new(BzDecoder::new(reader))))
+    } else if magic.starts_with(XZ_MAGIC) {
+        Ok(Box::new(BufReader::new(XzDecoder::new(reader))))
+    } else {
+        Ok(reader)
     }
 }
 
@@ -249,10 +309,52 @@ fn read_lines(path: &str) -> Result<Vec<String>> {
         .collect())
 }
 
+// --------------------------------------------------
+fn resolve_threads(threads: Option<usize>) -> usize {
+    threads.unwrap_or_else(|| {
+        thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    })
+}
+
+// --------------------------------------------------
+// Dispatches one call to `f` per file onto a pool of worker
+// threads and returns the results in the same order as `files`,
+// regardless of which worker finished which file first.
+fn process_files_parallel<T, F>(files: &[String], threads: usize, f: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(&str) -> T + Sync,
+{
+    if files.is_empty() {
+        return Vec::new();
+    }
+
+    let next = AtomicUsize::new(0);
+    let results: Vec<Mutex<Option<T>>> = files.iter().map(|_| Mutex::new(None)).collect();
+
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1).min(files.len()) {
+            scope.spawn(|| loop {
+                let idx = next.fetch_add(1, Ordering::SeqCst);
+                if idx >= files.len() {
+                    break;
+                }
+                *results[idx].lock().unwrap() = Some(f(&files[idx]));
+            });
+        }
+    });
+
+    results
+        .into_iter()
+        .map(|cell| cell.into_inner().unwrap().unwrap())
+        .collect()
+}
+
 // --------------------------------------------------
 fn filter(args: FilterArgs) -> Result<()> {
-    let mut reader =
-        parse_reader(open(&args.file).map_err(|e| anyhow!("{}: {e}", args.file))?)?;
+    let mut reader = parse_reader(open(&args.file).map_err(|e| anyhow!("{}: {e}", args.file))?)?;
 
     let mut output: Box<dyn Write> = match &args.output {
         Some(out_name) => Box::new(File::create(out_name)?),
@@ -266,16 +368,12 @@ fn filter(args: FilterArgs) -> Result<()> {
     };
     let id_lookup = HashSet::<String>::from_iter(ids);
     let id_filter = |rec: &Fastx| -> bool {
-        id_lookup.is_empty()
-            || id_lookup.contains(rec.head())
-            || id_lookup.contains(rec.des())
-    };
-    let max_len_filter = |rec: &Fastx| -> bool {
-        args.max_length == 0 || (rec.seq().len() <= args.max_length)
-    };
-    let min_len_filter = |rec: &Fastx| -> bool {
-        args.min_length == 0 || (rec.seq().len() >= args.min_length)
+        id_lookup.is_empty() || id_lookup.contains(rec.head()) || id_lookup.contains(rec.des())
     };
+    let max_len_filter =
+        |rec: &Fastx| -> bool { args.max_length == 0 || (rec.seq().len() <= args.max_length) };
+    let min_len_filter =
+        |rec: &Fastx| -> bool { args.min_length == 0 || (rec.seq().len() >= args.min_length) };
     let mut num_taken = 0;
 
     while let Some(rec) = reader.iter_record()? {
@@ -283,23 +381,7 @@ fn filter(args: FilterArgs) -> Result<()> {
             continue;
         }
 
-        if rec.is_fasta() {
-            writeln!(output, ">{}{}\n{}", rec.head(), rec.des(), rec.seq())?;
-        } else {
-            writeln!(
-                output,
-                "@{}{}\n{}\n{}\n{}",
-                rec.head(),
-                rec.des(),
-                rec.seq(),
-                if rec.sep().is_empty() { "+" } else { rec.sep() },
-                if rec.qual().is_empty() {
-                    "-".repeat(rec.seq().len())
-                } else {
-                    rec.qual().to_string()
-                },
-            )?;
-        }
+        write_seq(&rec, args.outfmt.as_ref(), &mut output)?;
 
         num_taken += 1;
         if !args.number == 0 || num_taken == args.number {
@@ -312,8 +394,7 @@ fn filter(args: FilterArgs) -> Result<()> {
 
 // --------------------------------------------------
 fn stats(args: StatsArgs) -> Result<()> {
-    let mut reader =
-        parse_reader(open(&args.file).map_err(|e| anyhow!("{}: {e}", args.file))?)?;
+    let mut reader = parse_reader(open(&args.file).map_err(|e| anyhow!("{}: {e}", args.file))?)?;
     let mut num_by_len: HashMap<usize, usize> = HashMap::new();
     let mut avg: i64 = 0;
     let mut counter = 0;
@@ -362,29 +443,64 @@ fn stats(args: StatsArgs) -> Result<()> {
     Ok(())
 }
 
+// --------------------------------------------------
+// Opens `filename`, parses it, and invokes `on_record` for each
+// record in turn. Any open/parse/read error is printed to stderr
+// as "{filename}: {e}" and ends the reading of that file; it is
+// not returned to the caller.
+fn for_each_record_in_file(filename: &str, mut on_record: impl FnMut(Fastx)) {
+    match open(filename) {
+        Err(e) => eprintln!("{filename}: {e}"),
+        Ok(file) => match parse_reader(file) {
+            Err(e) => eprintln!("{filename}: {e}"),
+            Ok(mut reader) => loop {
+                match reader.iter_record() {
+                    Ok(Some(rec)) => on_record(rec),
+                    Ok(None) => break,
+                    Err(e) => {
+                        eprintln!("{filename}: {e}");
+                        break;
+                    }
+                }
+            },
+        },
+    }
+}
+
+// --------------------------------------------------
+// Extracts the part of a record that `grep`/`grep --count`
+// search, per `--part`.
+fn search_text(rec: &Fastx, part: &Option<GrepRecordPart>) -> String {
+    match part {
+        Some(GrepRecordPart::Head) => format!("{}{}", rec.head(), rec.des()),
+        Some(GrepRecordPart::Sequence) => rec.seq().to_string(),
+        Some(GrepRecordPart::Quality) => rec.qual().to_string(),
+        _ => unreachable!(),
+    }
+}
+
+// --------------------------------------------------
+fn count_file(filename: &str) -> (String, usize) {
+    let mut num = 0;
+    for_each_record_in_file(filename, |_| num += 1);
+
+    let line = if filename == "-" {
+        format!("{num:>10}\n")
+    } else {
+        format!("{num:>10} {filename}\n")
+    };
+    (line, num)
+}
+
 // --------------------------------------------------
 fn count(args: CountArgs) -> Result<()> {
     let num_files = args.files.len();
+    let threads = resolve_threads(args.threads);
     let mut total = 0;
 
-    for filename in &args.files {
-        match open(filename) {
-            Err(e) => eprintln!("{filename}: {e}"),
-            Ok(file) => {
-                let mut reader = parse_reader(file)?;
-                let mut num = 0;
-                while (reader.iter_record()?).is_some() {
-                    num += 1;
-                }
-
-                if filename == "-" {
-                    println!("{num:>10}");
-                } else {
-                    println!("{num:>10} {filename}");
-                }
-                total += num;
-            }
-        }
+    for (line, num) in process_files_parallel(&args.files, threads, count_file) {
+        print!("{line}");
+        total += num;
     }
 
     if num_files > 1 {
@@ -394,6 +510,55 @@ fn count(args: CountArgs) -> Result<()> {
     Ok(())
 }
 
+// --------------------------------------------------
+fn grep_file(filename: &str, pattern: &Regex, args: &GrepArgs) -> Vec<u8> {
+    let pattern = pattern.clone();
+    let mut buf: Vec<u8> = Vec::new();
+
+    for_each_record_in_file(filename, |rec| {
+        let search = search_text(&rec, &args.part);
+
+        if args.only_matching {
+            if !args.invert {
+                for m in pattern.find_iter(&search) {
+                    let _ = writeln!(
+                        buf,
+                        "{}\t{}\t{}\t{}",
+                        rec.head(),
+                        m.start(),
+                        m.end(),
+                        m.as_str()
+                    );
+                }
+            }
+        } else if pattern.is_match(&search) ^ args.invert {
+            let _ = write_seq(&rec, args.outfmt.as_ref(), &mut buf);
+        }
+    });
+
+    buf
+}
+
+// --------------------------------------------------
+fn grep_count_file(filename: &str, pattern: &Regex, args: &GrepArgs) -> (String, usize) {
+    let pattern = pattern.clone();
+    let mut num = 0;
+
+    for_each_record_in_file(filename, |rec| {
+        let search = search_text(&rec, &args.part);
+        if pattern.is_match(&search) ^ args.invert {
+            num += 1;
+        }
+    });
+
+    let line = if filename == "-" || args.files.len() == 1 {
+        format!("{num:>10}\n")
+    } else {
+        format!("{num:>10} {filename}\n")
+    };
+    (line, num)
+}
+
 // --------------------------------------------------
 fn grep(args: GrepArgs) -> Result<()> {
     let pattern = RegexBuilder::new(&args.pattern)
@@ -406,51 +571,34 @@ fn grep(args: GrepArgs) -> Result<()> {
         _ => Box::new(io::stdout()),
     };
 
-    for filename in &args.files {
-        match open(filename) {
-            Err(e) => eprintln!("{filename}: {e}"),
-            Ok(file) => {
-                let mut reader = parse_reader(file)?;
-                //let mut outfmt = &args.outfmt;
-                while let Some(rec) = reader.iter_record()? {
-                    let search = match &args.part {
-                        Some(GrepRecordPart::Head) => {
-                            format!("{}{}", rec.head(), rec.des())
-                        }
-                        Some(GrepRecordPart::Sequence) => rec.seq().to_string(),
-                        Some(GrepRecordPart::Quality) => rec.qual().to_string(),
-                        _ => unreachable!(),
-                    };
-
-                    if pattern.is_match(&search) ^ args.invert {
-                        if rec.is_fasta() {
-                            writeln!(
-                                output,
-                                ">{}{}\n{}",
-                                rec.head(),
-                                rec.des(),
-                                rec.seq()
-                            )?;
-                        } else {
-                            writeln!(
-                                output,
-                                "@{}{}\n{}\n{}\n{}",
-                                rec.head(),
-                                rec.des(),
-                                rec.seq(),
-                                if rec.sep().is_empty() { "+" } else { rec.sep() },
-                                if rec.qual().is_empty() {
-                                    "-".repeat(rec.seq().len())
-                                } else {
-                                    rec.qual().to_string()
-                                },
-                            )?;
-                        }
-                    }
-                }
-            }
+    let threads = resolve_threads(args.threads);
+
+    if args.count {
+        let num_files = args.files.len();
+        let mut total = 0;
+
+        for (line, num) in process_files_parallel(&args.files, threads, |filename| {
+            grep_count_file(filename, &pattern, &args)
+        }) {
+            write!(output, "{line}")?;
+            total += num;
         }
+
+        if num_files > 1 {
+            writeln!(output, "{total:>10}: total")?;
+        }
+
+        return Ok(());
     }
+
+    let buffers = process_files_parallel(&args.files, threads, |filename| {
+        grep_file(filename, &pattern, &args)
+    });
+
+    for buf in buffers {
+        output.write_all(&buf)?;
+    }
+
     Ok(())
 }
 
@@ -477,29 +625,33 @@ fn headers(args: HeadersArgs) -> Result<()> {
     Ok(())
 }
 
-//fn write_seq(
-//    rec: kseq::Fastx,
-//    output: impl Write,
-//) -> Result<()> {
-//    match format {
-//        Some(OutputFormat::Fasta) => {
-//            writeln!(output, ">{}{}\n{}", rec.head(), rec.des(), rec.seq())?;
-//        }
-//        Some(OutputFormat::Fastq) => {
-//            writeln!(
-//                output,
-//                "@{}{}\n{}\n{}\n{}",
-//                rec.head(),
-//                rec.des(),
-//                rec.seq(),
-//                if rec.sep().is_empty() { "+" } else { rec.sep() },
-//                if rec.qual().is_empty() {
-//                    "-".repeat(rec.seq().len())
-//                } else {
-//                    rec.qual().to_string()
-//                },
-//            )?;
-//        }
-//    }
-//    Ok(())
-//}
+// --------------------------------------------------
+// Writes a record in the given format, or in its own native
+// format (FASTA/FASTQ) when no format is given.
+fn write_seq(rec: &Fastx, fmt: Option<&OutputFormat>, output: &mut dyn Write) -> Result<()> {
+    let as_fasta = match fmt {
+        Some(OutputFormat::Fasta) => true,
+        Some(OutputFormat::Fastq) => false,
+        None => rec.is_fasta(),
+    };
+
+    if as_fasta {
+        writeln!(output, ">{}{}\n{}", rec.head(), rec.des(), rec.seq())?;
+    } else {
+        writeln!(
+            output,
+            "@{}{}\n{}\n{}\n{}",
+            rec.head(),
+            rec.des(),
+            rec.seq(),
+            if rec.sep().is_empty() { "+" } else { rec.sep() },
+            if rec.qual().is_empty() {
+                "-".repeat(rec.seq().len())
+            } else {
+                rec.qual().to_string()
+            },
+        )?;
+    }
+
+    Ok(())
+}
@@ -1,10 +1,12 @@
 use anyhow::Result;
 use assert_cmd::Command;
+use flate2::{write::GzEncoder, Compression};
 use predicates::prelude::*;
 use pretty_assertions::assert_eq;
 use rand::{distributions::Alphanumeric, Rng};
 use regex::Regex;
 use std::fs;
+use std::io::Write;
 use tempfile::NamedTempFile;
 
 const PRG: &str = "seqr";
@@ -12,6 +14,9 @@ const DFAM: &str = "tests/inputs/dfam.fa";
 const OUT_DFAM_ALU_FA: &str = "tests/outputs/dfam.alu.fa";
 const OUT_DFAM_ALU_FQ: &str = "tests/outputs/dfam.alu.fq";
 const OUT_DFAM_ALU_I_FA: &str = "tests/outputs/dfam.alu.insensitive.fa";
+const OUT_DFAM_ALU_MATCHES: &str = "tests/outputs/dfam.alu.matches.txt";
+const OUT_DFAM_FILTER_FA: &str = "tests/outputs/dfam.filter.fa";
+const OUT_DFAM_FILTER_FQ: &str = "tests/outputs/dfam.filter.fq";
 
 // --------------------------------------------------
 fn gen_nonexistent_file() -> String {
@@ -74,11 +79,7 @@ fn run_stdout(args: &[&str], expected_file: &str) -> Result<()> {
 }
 
 // --------------------------------------------------
-fn run_stdin(
-    input_file: &str,
-    args: &[&str],
-    expected_file: &str,
-) -> Result<()> {
+fn run_stdin(input_file: &str, args: &[&str], expected_file: &str) -> Result<()> {
     let input = fs::read_to_string(input_file)?;
     let expected = fs::read_to_string(expected_file)?;
     let output = Command::cargo_bin(PRG)?
@@ -114,6 +115,16 @@ fn run_outfile(args: &[&str], expected_file: &str) -> Result<()> {
     Ok(())
 }
 
+// --------------------------------------------------
+fn gzip_fixture(path: &str) -> Result<NamedTempFile> {
+    let data = fs::read(path)?;
+    let gz_file = NamedTempFile::new()?;
+    let mut encoder = GzEncoder::new(fs::File::create(gz_file.path())?, Compression::default());
+    encoder.write_all(&data)?;
+    encoder.finish()?;
+    Ok(gz_file)
+}
+
 // --------------------------------------------------
 #[test]
 fn grep_dies_bad_outfmt() -> Result<()> {
@@ -157,3 +168,191 @@ fn grep_alu_stdin() -> Result<()> {
 fn grep_alu_outfile() -> Result<()> {
     run_outfile(&["grep", "Alu", DFAM], OUT_DFAM_ALU_FA)
 }
+
+// --------------------------------------------------
+#[test]
+fn grep_alu_only_matching_stdout() -> Result<()> {
+    run_stdout(
+        &["grep", "-m", "-p", "seq", "Alu", DFAM],
+        OUT_DFAM_ALU_MATCHES,
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn grep_alu_only_matching_invert_emits_nothing() -> Result<()> {
+    let output = Command::cargo_bin(PRG)?
+        .args(["grep", "-m", "-v", "Alu", DFAM])
+        .output()?;
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn grep_multi_file_threads_preserve_order() -> Result<()> {
+    let mut file_a = NamedTempFile::new()?;
+    writeln!(file_a, ">seq1\nACGTACGT")?;
+    let mut file_b = NamedTempFile::new()?;
+    writeln!(file_b, ">seq2\nACGTACGT")?;
+
+    let path_a = file_a.path().to_str().unwrap().to_string();
+    let path_b = file_b.path().to_str().unwrap().to_string();
+
+    let sequential = Command::cargo_bin(PRG)?
+        .args(["grep", "-j", "1", "-p", "seq", "ACGT", &path_a, &path_b])
+        .output()?;
+    let parallel = Command::cargo_bin(PRG)?
+        .args(["grep", "-j", "4", "-p", "seq", "ACGT", &path_a, &path_b])
+        .output()?;
+
+    assert!(sequential.status.success());
+    assert!(parallel.status.success());
+    assert_eq!(parallel.stdout, sequential.stdout);
+    assert_eq!(
+        String::from_utf8(parallel.stdout)?,
+        ">seq1\nACGTACGT\n>seq2\nACGTACGT\n"
+    );
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn grep_alu_gzip_matches_plain() -> Result<()> {
+    let gz_file = gzip_fixture(DFAM)?;
+    let gz_path = gz_file.path().to_str().unwrap();
+
+    let plain = Command::cargo_bin(PRG)?
+        .args(["grep", "Alu", DFAM])
+        .output()?;
+    let compressed = Command::cargo_bin(PRG)?
+        .args(["grep", "Alu", gz_path])
+        .output()?;
+
+    assert!(plain.status.success());
+    assert!(compressed.status.success());
+    assert_eq!(compressed.stdout, plain.stdout);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn count_gzip_matches_plain() -> Result<()> {
+    let gz_file = gzip_fixture(DFAM)?;
+    let gz_path = gz_file.path().to_str().unwrap();
+
+    let plain = Command::cargo_bin(PRG)?.args(["count", DFAM]).output()?;
+    let compressed = Command::cargo_bin(PRG)?.args(["count", gz_path]).output()?;
+
+    assert!(plain.status.success());
+    assert!(compressed.status.success());
+
+    let plain_count = String::from_utf8(plain.stdout)?
+        .split_whitespace()
+        .next()
+        .unwrap()
+        .to_string();
+    let compressed_count = String::from_utf8(compressed.stdout)?
+        .split_whitespace()
+        .next()
+        .unwrap()
+        .to_string();
+    assert_eq!(compressed_count, plain_count);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn grep_count_single_file() -> Result<()> {
+    let output = Command::cargo_bin(PRG)?
+        .args(["grep", "-c", "Alu", DFAM])
+        .output()?;
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout)?;
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 1);
+    assert!(Regex::new(r"^\s*\d+$")?.is_match(lines[0]));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn grep_count_multi_file_total() -> Result<()> {
+    let output = Command::cargo_bin(PRG)?
+        .args(["grep", "-c", "Alu", DFAM, DFAM])
+        .output()?;
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout)?;
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].ends_with(DFAM));
+    assert!(lines[1].ends_with(DFAM));
+    assert!(lines[2].trim_end().ends_with(": total"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn grep_count_combines_with_invert_part_insensitive() -> Result<()> {
+    let output = Command::cargo_bin(PRG)?
+        .args(["grep", "-c", "-v", "-i", "-p", "seq", "alu", DFAM])
+        .output()?;
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(Regex::new(r"^\s*\d+$")?.is_match(stdout.trim_end()));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn grep_count_dies_with_only_matching() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .args(["grep", "-c", "-m", "Alu", DFAM])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn filter_outfmt_fastq_stdout() -> Result<()> {
+    run_stdout(&["filter", "-t", "fastq", DFAM], OUT_DFAM_FILTER_FQ)
+}
+
+// --------------------------------------------------
+#[test]
+fn filter_outfmt_fasta_stdout() -> Result<()> {
+    run_stdout(&["filter", "-t", "fasta", DFAM], OUT_DFAM_FILTER_FA)
+}
+
+// --------------------------------------------------
+#[test]
+fn filter_outfmt_round_trip() -> Result<()> {
+    let fastq = Command::cargo_bin(PRG)?
+        .args(["filter", "-t", "fastq", DFAM])
+        .output()?;
+    assert!(fastq.status.success());
+
+    let fasta = Command::cargo_bin(PRG)?
+        .args(["filter", "-t", "fasta"])
+        .write_stdin(fastq.stdout)
+        .output()?;
+    assert!(fasta.status.success());
+
+    let expected = fs::read_to_string(OUT_DFAM_FILTER_FA)?;
+    assert_eq!(String::from_utf8(fasta.stdout)?, expected);
+
+    Ok(())
+}